@@ -1,11 +1,16 @@
-use std::io;
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use globset::GlobSet;
 use grep::matcher::Matcher;
+#[cfg(feature = "pcre2")]
+use grep::pcre2::RegexMatcher as PCRE2RegexMatcher;
 use grep::printer::{JSON, Standard, Summary, Stats};
 use grep::regex::RegexMatcher;
 use grep::searcher::Searcher;
+use serde_json::json;
 use termcolor::WriteColor;
 
 use decompressor::{DecompressionReader, is_compressed};
@@ -18,14 +23,18 @@ use subject::Subject;
 #[derive(Clone, Debug)]
 struct Config {
     preprocessor: Option<PathBuf>,
+    preprocessor_globs: GlobSet,
     search_zip: bool,
+    decompression_commands: HashMap<String, PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
             preprocessor: None,
+            preprocessor_globs: GlobSet::empty(),
             search_zip: false,
+            decompression_commands: HashMap::new(),
         }
     }
 }
@@ -73,6 +82,25 @@ impl SearchWorkerBuilder {
         self
     }
 
+    /// Set the globs that restrict which paths get routed through the
+    /// preprocessor.
+    ///
+    /// When this is non-empty, only subjects whose path matches one of
+    /// these globs are sent through the preprocessor command. Subjects that
+    /// don't match fall through to the next applicable strategy instead —
+    /// a configured decompression command, then built-in decompression, then
+    /// `search_path` — so they aren't forced through the preprocessor, but
+    /// they aren't guaranteed to hit `search_path`'s memory-map fast path
+    /// either. When this is empty (the default), every non-stdin subject is
+    /// sent through the preprocessor, as before.
+    pub fn preprocessor_globs(
+        &mut self,
+        globs: GlobSet,
+    ) -> &mut SearchWorkerBuilder {
+        self.config.preprocessor_globs = globs;
+        self
+    }
+
     /// Enable the decompression and searching of common compressed files.
     ///
     /// When enabled, if a particular file path is recognized as a compressed
@@ -84,6 +112,24 @@ impl SearchWorkerBuilder {
         self.config.search_zip = yes;
         self
     }
+
+    /// Set a map from file extension (without the leading dot) to an
+    /// external decompression command.
+    ///
+    /// When a subject's path has an extension present in this map, the
+    /// corresponding command is run with the file path as its first
+    /// argument, and its output is searched instead, much like the
+    /// preprocessor. This is consulted before the built-in decompression
+    /// support enabled by `search_zip`, so it can be used to add support for
+    /// formats ripgrep doesn't decompress natively (e.g. `.br` or `.7z`) or
+    /// to override the command used for a format it does.
+    pub fn decompression_commands(
+        &mut self,
+        commands: HashMap<String, PathBuf>,
+    ) -> &mut SearchWorkerBuilder {
+        self.config.decompression_commands = commands;
+        self
+    }
 }
 
 /// The result of executing a search.
@@ -117,6 +163,8 @@ impl SearchResult {
 #[derive(Clone, Debug)]
 pub enum PatternMatcher {
     RustRegex(RegexMatcher),
+    #[cfg(feature = "pcre2")]
+    PCRE2(PCRE2RegexMatcher),
 }
 
 /// The printer used by a search worker.
@@ -146,13 +194,35 @@ impl<W: WriteColor> Printer<W> {
         stats: &Stats,
     ) -> io::Result<()> {
         match *self {
-            Printer::JSON(_) => unimplemented!(),
+            Printer::JSON(_) => self.print_stats_json(total_duration, stats),
             Printer::Standard(_) | Printer::Summary(_) => {
                 self.print_stats_human(total_duration, stats)
             }
         }
     }
 
+    /// Print the given statistics as a single JSON Lines record, consistent
+    /// with the format used by the rest of the JSON printer's output.
+    fn print_stats_json(
+        &mut self,
+        total_duration: Duration,
+        stats: &Stats,
+    ) -> io::Result<()> {
+        let msg = json!({
+            "type": "summary",
+            "data": {
+                "stats": stats,
+                "elapsed_total": {
+                    "secs": total_duration.as_secs(),
+                    "nanos": total_duration.subsec_nanos(),
+                    "human": format!("{:.6}s", fractional_seconds(total_duration)),
+                },
+            },
+        });
+        serde_json::to_writer(&mut *self.get_mut(), &msg)?;
+        self.get_mut().write_all(b"\n")
+    }
+
     fn print_stats_human(
         &mut self,
         total_duration: Duration,
@@ -222,10 +292,16 @@ impl<W: WriteColor> SearchWorker<W> {
             let stdin = io::stdin();
             // A `return` here appeases the borrow checker. NLL will fix this.
             return self.search_reader(path, stdin.lock());
-        } else if self.config.preprocessor.is_some() {
+        } else if self.config.preprocessor.is_some()
+            && (self.config.preprocessor_globs.is_empty()
+                || self.config.preprocessor_globs.is_match(path))
+        {
             let cmd = self.config.preprocessor.clone().unwrap();
             let rdr = PreprocessorReader::from_cmd_path(cmd, path)?;
             self.search_reader(path, rdr)
+        } else if let Some(cmd) = self.decompression_command_for(path) {
+            let rdr = PreprocessorReader::from_cmd_path(cmd, path)?;
+            self.search_reader(path, rdr)
         } else if self.config.search_zip && is_compressed(path) {
             match DecompressionReader::from_path(path) {
                 None => Ok(SearchResult::default()),
@@ -236,6 +312,13 @@ impl<W: WriteColor> SearchWorker<W> {
         }
     }
 
+    /// Look up a user-configured decompression command for the given path's
+    /// extension, if one has been registered.
+    fn decompression_command_for(&self, path: &Path) -> Option<PathBuf> {
+        let ext = path.extension()?.to_str()?;
+        self.config.decompression_commands.get(ext).cloned()
+    }
+
     /// Search the contents of the given file path.
     fn search_path(&mut self, path: &Path) -> io::Result<SearchResult> {
         use self::PatternMatcher::*;
@@ -243,6 +326,8 @@ impl<W: WriteColor> SearchWorker<W> {
         let (searcher, printer) = (&mut self.searcher, &mut self.printer);
         match self.matcher {
             RustRegex(ref m) => search_path(m, searcher, printer, path),
+            #[cfg(feature = "pcre2")]
+            PCRE2(ref m) => search_path(m, searcher, printer, path),
         }
     }
 
@@ -265,6 +350,8 @@ impl<W: WriteColor> SearchWorker<W> {
         let (searcher, printer) = (&mut self.searcher, &mut self.printer);
         match self.matcher {
             RustRegex(ref m) => search_reader(m, searcher, printer, path, rdr),
+            #[cfg(feature = "pcre2")]
+            PCRE2(ref m) => search_reader(m, searcher, printer, path, rdr),
         }
     }
 }